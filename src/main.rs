@@ -1,3 +1,4 @@
+extern crate atty;
 extern crate getopts;
 extern crate image;
 extern crate regex;
@@ -14,7 +15,11 @@ use image::{
 use regex::Regex;
 use rustc_serialize::hex::FromHex;
 use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
 use std::path::Path;
+use std::process;
 
 const VERSION: &'static str = env!("CARGO_PKG_VERSION");
 
@@ -24,17 +29,40 @@ const ON_COLOR: &'static str = "#ffffff";
 const OUTPUT: &'static str = "output.png";
 const SIZE: u32 = 2048;
 
+/// The 6 color levels used by the xterm 256-color cube.
+const ANSI_CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// How the rendered fractal should be written out.
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    Png,
+    Ansi,
+    Ansi256,
+}
+
 /// The options used when creating an image.
 struct ImageOptions {
     match_color: [u8; 3],
     off_color: [u8; 3],
     on_color: [u8; 3],
+    palette: Option<Vec<[u8; 3]>>,
     regex: String,
     size: u32,
 }
 
-/// Create and save the image using the options from the command line.
-fn do_work(matches: Matches) {
+/// The `--on-color`/`--off-color`/`--match-color`/`-s`/`--palette` options,
+/// resolved and validated the same way for both single-shot and batch runs.
+struct CommonOptions {
+    match_color: [u8; 3],
+    off_color: [u8; 3],
+    on_color: [u8; 3],
+    palette: Option<Vec<[u8; 3]>>,
+    size: u32,
+}
+
+/// Resolve the color/size/palette options shared by `do_work` and
+/// `do_batch`, exiting with a descriptive error on malformed input.
+fn resolve_common_options(matches: &Matches) -> CommonOptions {
     let match_color = match matches.opt_str("match-color") {
         Some(s) => s,
         None => String::from(MATCH_COLOR)
@@ -50,26 +78,261 @@ fn do_work(matches: Matches) {
         None => String::from(ON_COLOR)
     };
 
+    let size = match matches.opt_str("s") {
+        Some(s) => match s.parse::<u32>() {
+            Ok(size) if size > 0 => size,
+            _ => {
+                let _ = writeln!(io::stderr(), "failed to parse size \"{}\"; expected a positive integer", s);
+                process::exit(1);
+            }
+        },
+        None => SIZE
+    };
+
+    let palette = match matches.opt_str("palette") {
+        Some(path) => Some(load_palette(&path)),
+        None => None
+    };
+
+    CommonOptions {
+        match_color: parse_color_or_exit(&match_color),
+        off_color: parse_color_or_exit(&off_color),
+        on_color: parse_color_or_exit(&on_color),
+        palette: palette,
+        size: size
+    }
+}
+
+/// Resolve the requested output format from `--format`/`--ansi-256`,
+/// defaulting to ANSI when the output path is `-`.
+fn resolve_format(matches: &Matches, output: &str) -> OutputFormat {
+    match matches.opt_str("format").as_ref().map(String::as_str) {
+        Some("ansi") => OutputFormat::Ansi,
+        Some("ansi-256") => OutputFormat::Ansi256,
+        Some("png") => OutputFormat::Png,
+        Some(other) => {
+            let _ = writeln!(io::stderr(), "unknown --format \"{}\"; expected png, ansi, or ansi-256", other);
+            process::exit(1);
+        }
+        None if matches.opt_present("ansi-256") => OutputFormat::Ansi256,
+        None if output == "-" => OutputFormat::Ansi,
+        None => OutputFormat::Png,
+    }
+}
+
+/// Compile `pattern`, printing a descriptive error and exiting with a
+/// non-zero status instead of panicking on a malformed regex.
+fn compile_regex_or_exit(pattern: &str) -> Regex {
+    match Regex::new(pattern) {
+        Ok(regex) => regex,
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "invalid regex \"{}\": {}", pattern, e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Create and save the image using the options from the command line.
+fn do_work(matches: Matches) {
     let output = match matches.opt_str("o") {
         Some(s) => s,
         None => String::from(OUTPUT)
     };
 
-    let size = match matches.opt_str("s") {
-        Some(s) => s.parse::<u32>().unwrap(),
-        None => SIZE
-    };
+    let format = resolve_format(&matches, &output);
+    let common = resolve_common_options(&matches);
+    let pattern = matches.free[0].clone();
+
+    compile_regex_or_exit(&pattern);
 
     let image_options = ImageOptions {
-        match_color: to_color(match_color),
-        off_color: to_color(off_color),
-        on_color: to_color(on_color),
-        regex: matches.free[0].clone(),
-        size: size
+        match_color: common.match_color,
+        off_color: common.off_color,
+        on_color: common.on_color,
+        palette: common.palette,
+        regex: pattern,
+        size: common.size
     };
 
     let image = create_image(image_options);
-    let _ = image.save(&Path::new(&output));
+
+    match format {
+        OutputFormat::Png => {
+            let _ = image.save(&Path::new(&output));
+        }
+        OutputFormat::Ansi => write_ansi(&image, false),
+        OutputFormat::Ansi256 => write_ansi(&image, true),
+    }
+}
+
+/// Read one regex per line from stdin and render an image for each,
+/// reporting invalid regexes to stderr and skipping them rather than
+/// aborting the whole run.
+fn do_batch(matches: Matches) {
+    let output_template = match matches.opt_str("o") {
+        Some(s) => s,
+        None => String::from(OUTPUT)
+    };
+
+    let format = resolve_format(&matches, &output_template);
+
+    if format == OutputFormat::Png && !output_template.contains('.') {
+        let _ = writeln!(
+            io::stderr(),
+            "--output \"{}\" has no extension; batch PNG mode needs a template like \"out.png\" or \"out-{{}}.png\"",
+            output_template
+        );
+        process::exit(1);
+    }
+
+    let common = resolve_common_options(&matches);
+
+    let stdin = io::stdin();
+    let mut index = 0;
+
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break
+        };
+
+        let pattern = line.trim();
+
+        if pattern.is_empty() {
+            continue;
+        }
+
+        if Regex::new(pattern).is_err() {
+            let _ = writeln!(io::stderr(), "skipping invalid regex \"{}\"", pattern);
+            continue;
+        }
+
+        let image_options = ImageOptions {
+            match_color: common.match_color,
+            off_color: common.off_color,
+            on_color: common.on_color,
+            palette: common.palette.clone(),
+            regex: String::from(pattern),
+            size: common.size
+        };
+
+        let image = create_image(image_options);
+
+        match format {
+            OutputFormat::Png => {
+                let output = batch_output_name(&output_template, index);
+                let _ = image.save(&Path::new(&output));
+            }
+            OutputFormat::Ansi => write_ansi(&image, false),
+            OutputFormat::Ansi256 => write_ansi(&image, true),
+        }
+
+        index += 1;
+    }
+}
+
+/// Derive a batch output filename from the `-o` template, substituting
+/// `{}` with the zero-padded index if present, or inserting a numeric
+/// suffix before the extension otherwise.
+fn batch_output_name(template: &str, index: usize) -> String {
+    if template.contains("{}") {
+        return template.replace("{}", &format!("{:03}", index));
+    }
+
+    match template.rfind('.') {
+        Some(dot) => format!("{}-{:03}{}", &template[..dot], index, &template[dot..]),
+        None => format!("{}-{:03}", template, index)
+    }
+}
+
+/// Render the image to stdout as a half-block glyph per two vertical pixels,
+/// using either truecolor or the xterm 256-color cube.
+fn write_ansi(image: &RgbImage, use_256: bool) {
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    let width = image.width();
+    let height = image.height();
+    let mut y = 0;
+
+    while y < height {
+        let mut line = String::new();
+
+        for x in 0..width {
+            let top = image.get_pixel(x, y).0;
+            let bottom = if y + 1 < height {
+                Some(image.get_pixel(x, y + 1).0)
+            } else {
+                None
+            };
+
+            if use_256 {
+                line.push_str(&format!("\x1b[38;5;{}m", rgb_to_ansi256(top)));
+                match bottom {
+                    Some(b) => line.push_str(&format!("\x1b[48;5;{}m", rgb_to_ansi256(b))),
+                    None => line.push_str("\x1b[49m"),
+                }
+            } else {
+                line.push_str(&format!("\x1b[38;2;{};{};{}m", top[0], top[1], top[2]));
+                match bottom {
+                    Some(b) => line.push_str(&format!("\x1b[48;2;{};{};{}m", b[0], b[1], b[2])),
+                    None => line.push_str("\x1b[49m"),
+                }
+            }
+
+            line.push('\u{2580}');
+        }
+
+        line.push_str("\x1b[0m\n");
+        let _ = out.write_all(line.as_bytes());
+
+        y += 2;
+    }
+}
+
+/// Quantize an RGB triple to the nearest xterm 256-color palette index,
+/// routing near-grey colors to the 232..255 grayscale ramp.
+fn rgb_to_ansi256(rgb: [u8; 3]) -> u8 {
+    let r = rgb[0] as i32;
+    let g = rgb[1] as i32;
+    let b = rgb[2] as i32;
+
+    if (r - g).abs() < 10 && (g - b).abs() < 10 && (r - b).abs() < 10 {
+        let avg = (r + g + b) / 3;
+
+        // The ramp's 24 levels are 8, 18, ..., 238; pure black and white
+        // come from the cube when they are nearer.
+        if avg < 4 {
+            return 16;
+        } else if avg > 246 {
+            return 231;
+        } else {
+            return 232 + ((avg - 3) / 10).min(23) as u8;
+        }
+    }
+
+    let ri = nearest_cube_level(rgb[0]);
+    let gi = nearest_cube_level(rgb[1]);
+    let bi = nearest_cube_level(rgb[2]);
+
+    16 + 36 * ri + 6 * gi + bi
+}
+
+/// Find the index of the nearest of the 6 xterm color cube levels for a channel.
+fn nearest_cube_level(channel: u8) -> u8 {
+    let mut best = 0;
+    let mut best_dist = 256;
+
+    for (i, &level) in ANSI_CUBE_LEVELS.iter().enumerate() {
+        let dist = (channel as i32 - level as i32).abs() as usize;
+
+        if dist < best_dist {
+            best_dist = dist;
+            best = i;
+        }
+    }
+
+    best as u8
 }
 
 /// Create the image with the given options.
@@ -81,18 +344,55 @@ fn create_image(options: ImageOptions) -> RgbImage {
 
         if regex.is_match(pixel_id.as_str()) {
             let captures = regex.captures(pixel_id.as_str()).unwrap();
-            let t = match captures.at(1) {
-                Some(c) => c.len() as f64 / pixel_id.len() as f64,
-                None => 0 as f64
+
+            let named_r = captures.name("r");
+            let named_g = captures.name("g");
+            let named_b = captures.name("b");
+            let has_named = named_r.is_some() || named_g.is_some() || named_b.is_some();
+
+            let (r_group, g_group, b_group) = if has_named {
+                (named_r, named_g, named_b)
+            } else {
+                (captures.at(1), captures.at(2), captures.at(3))
             };
 
-            Rgb(color_lerp(t, options.on_color, options.match_color))
+            let per_channel = has_named || g_group.is_some() || b_group.is_some();
+
+            let color = if per_channel {
+                channel_color(pixel_id.len(), r_group, g_group, b_group)
+            } else {
+                let t = match captures.at(1) {
+                    Some(c) => c.len() as f64 / pixel_id.len() as f64,
+                    None => 0 as f64
+                };
+
+                match options.palette {
+                    Some(ref stops) => gradient_color(t, stops),
+                    None => color_lerp(t, options.on_color, options.match_color)
+                }
+            };
+
+            Rgb(color)
         } else {
             Rgb(options.off_color)
         }
     })
 }
 
+/// Derive a color by driving the R, G, and B channels independently from
+/// the matched length of up to three capture groups, each as a fraction
+/// of the full pixel id length.
+fn channel_color(pixel_len: usize, r: Option<&str>, g: Option<&str>, b: Option<&str>) -> [u8; 3] {
+    let len = pixel_len as f64;
+
+    let channel = |group: Option<&str>| match group {
+        Some(s) => (s.len() as f64 / len * 255.0) as u8,
+        None => 0
+    };
+
+    [channel(r), channel(g), channel(b)]
+}
+
 fn color_lerp(t: f64, fst_color: [u8; 3], snd_color: [u8; 3]) -> [u8; 3] {
     [u8_lerp(t, fst_color[0], snd_color[0]),
      u8_lerp(t, fst_color[1], snd_color[1]),
@@ -149,12 +449,148 @@ fn print_version(program: &str) {
     println!("{} v{}", program, VERSION);
 }
 
-/// Convert a string such as "#f0f0f0" or "99cc33" to a u8 array.
-fn to_color(text: String) -> [u8; 3] {
-    let base = text.len() - 6;
-    let hex = text[base..base+6].from_hex().unwrap();
+/// An error produced when a color argument could not be parsed.
+#[derive(Debug)]
+struct ColorError {
+    input: String,
+}
+
+impl fmt::Display for ColorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to parse color \"{}\"; expected #rrggbb, #rgb, or a named color", self.input)
+    }
+}
+
+/// Look up one of the 16 standard terminal color names, including their
+/// `bright-*` variants.
+fn named_color(name: &str) -> Option<[u8; 3]> {
+    match name {
+        "black" => Some([0, 0, 0]),
+        "red" => Some([170, 0, 0]),
+        "green" => Some([0, 170, 0]),
+        "yellow" => Some([170, 85, 0]),
+        "blue" => Some([0, 0, 170]),
+        "magenta" => Some([170, 0, 170]),
+        "cyan" => Some([0, 170, 170]),
+        "white" => Some([170, 170, 170]),
+        "bright-black" => Some([85, 85, 85]),
+        "bright-red" => Some([255, 85, 85]),
+        "bright-green" => Some([85, 255, 85]),
+        "bright-yellow" => Some([255, 255, 85]),
+        "bright-blue" => Some([85, 85, 255]),
+        "bright-magenta" => Some([255, 85, 255]),
+        "bright-cyan" => Some([85, 255, 255]),
+        "bright-white" => Some([255, 255, 255]),
+        _ => None
+    }
+}
+
+/// Parse a color given as `#rrggbb`, `#rgb` (each nibble doubled), an
+/// optional `0x`-prefixed hex string, or a named color.
+fn parse_color(text: &str) -> Result<[u8; 3], ColorError> {
+    let trimmed = text.trim();
+
+    if let Some(color) = named_color(trimmed) {
+        return Ok(color);
+    }
+
+    let hex_part = if trimmed.starts_with('#') {
+        &trimmed[1..]
+    } else if trimmed.starts_with("0x") {
+        &trimmed[2..]
+    } else {
+        trimmed
+    };
+
+    let expanded = match hex_part.len() {
+        3 => {
+            let mut expanded = String::with_capacity(6);
+
+            for c in hex_part.chars() {
+                expanded.push(c);
+                expanded.push(c);
+            }
+
+            expanded
+        }
+        6 => String::from(hex_part),
+        _ => return Err(ColorError { input: String::from(text) })
+    };
+
+    match expanded.from_hex() {
+        Ok(bytes) => Ok([bytes[0], bytes[1], bytes[2]]),
+        Err(_) => Err(ColorError { input: String::from(text) })
+    }
+}
+
+/// Parse a color argument, printing a descriptive error and exiting with a
+/// non-zero status instead of panicking on malformed input.
+fn parse_color_or_exit(text: &str) -> [u8; 3] {
+    match parse_color(text) {
+        Ok(color) => color,
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// Load a multi-stop gradient palette from a file, one hex or named color
+/// per line, ignoring blank lines and `#`-comment lines. Exits with a
+/// descriptive error if the file can't be read or a color can't be parsed.
+fn load_palette(path: &str) -> Vec<[u8; 3]> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "failed to read palette \"{}\": {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    let mut stops = Vec::new();
+
+    for line in BufReader::new(file).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                let _ = writeln!(io::stderr(), "failed to read palette \"{}\": {}", path, e);
+                process::exit(1);
+            }
+        };
+
+        let trimmed = line.trim();
+
+        // A `#` line is a comment unless it parses as a `#rrggbb`/`#rgb` color.
+        if trimmed.is_empty() || (trimmed.starts_with('#') && parse_color(trimmed).is_err()) {
+            continue;
+        }
+
+        stops.push(parse_color_or_exit(trimmed));
+    }
+
+    if stops.is_empty() {
+        let _ = writeln!(io::stderr(), "failed to read palette \"{}\": no colors found", path);
+        process::exit(1);
+    }
+
+    stops
+}
+
+/// Map `t` (clamped to `[0, 1]`) across an ordered list of gradient stops,
+/// interpolating between the two stops the point falls between.
+fn gradient_color(t: f64, stops: &[[u8; 3]]) -> [u8; 3] {
+    let t = t.max(0.0).min(1.0);
+
+    if stops.len() == 1 {
+        return stops[0];
+    }
+
+    let scaled = t * (stops.len() - 1) as f64;
+    let i = scaled.floor() as usize;
+    let i = i.min(stops.len() - 2);
+    let f = scaled - i as f64;
 
-    [hex[0], hex[1], hex[2]]
+    color_lerp(f, stops[i], stops[i + 1])
 }
 
 /// Set up the options and check if usage is correct.
@@ -167,8 +603,12 @@ fn main() {
     opts.optopt("", "on-color", "set color for pixels with matches", "COLOR");
     opts.optopt("", "off-color", "set color for pixels without matches", "COLOR");
     opts.optopt("", "match-color", "set color for pixels colored by match size", "COLOR");
-    opts.optopt("o", "output", "set output filename", "FILE");
+    opts.optopt("o", "output", "set output filename, or - for the terminal", "FILE");
     opts.optopt("s", "size", "set output image size", "SIZE");
+    opts.optopt("", "format", "set output format: png, ansi, or ansi-256", "FORMAT");
+    opts.optopt("", "palette", "load a multi-stop gradient palette from FILE", "FILE");
+    opts.optflag("", "ansi-256", "render to the terminal using the xterm 256-color cube");
+    opts.optflag("", "stdin", "read one regex per line from stdin and render a batch of images");
     opts.optflag("h", "help", "print this help menu and quit");
     opts.optflag("v", "version", "print this program version and quit");
 
@@ -189,6 +629,8 @@ fn main() {
 
     if !matches.free.is_empty() {
         do_work(matches);
+    } else if matches.opt_present("stdin") || !atty::is(atty::Stream::Stdin) {
+        do_batch(matches);
     } else {
         print_usage(&program, opts);
         return;